@@ -1,15 +1,26 @@
 use futures::StreamExt;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use reqwest::{self, Client};
 use std::path::Path;
 use std::io::Write;
-use std::fs::File;
+use std::fs::{self, File};
+use std::time::Duration;
 use core::fmt;
 use std::error::Error;
-use log::debug;
+use log::{debug, warn};
 use std::string::ToString;
+use indicatif::ProgressBar;
 
-#[derive(Deserialize, Debug)]
+use crate::ratelimit::RateLimiter;
+
+/// Credentials used to authenticate requests via HTTP Basic auth, unlocking
+/// posts that are hidden from anonymous users by the default blacklist.
+pub struct Credentials {
+    pub login: String,
+    pub api_key: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
 pub struct PostFile {
     pub width: u32,
     pub height: u32,
@@ -19,14 +30,14 @@ pub struct PostFile {
     pub url: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct PostPreview {
     pub width: u32,
     pub height: u32,
     pub url: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct PostSample {
     pub has: bool,
     pub width: u32,
@@ -34,14 +45,14 @@ pub struct PostSample {
     pub url: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct PostScore {
     pub up: u32,
     pub down: i32,
     pub total: i32,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct PostTags {
     pub general: Vec<String>,
     pub species: Vec<String>,
@@ -66,7 +77,7 @@ impl PostTags {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct PostFlags {
     pub pending: bool,
     pub flagged: bool,
@@ -76,7 +87,7 @@ pub struct PostFlags {
     pub deleted: bool,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct PostRelationships {
     pub parent_id: Option<u32>,
     pub has_children: bool,
@@ -84,7 +95,7 @@ pub struct PostRelationships {
     pub children: Vec<u32>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Post {
     pub id: u32,
     pub description: String,
@@ -111,7 +122,7 @@ pub struct Post {
     pub duration: Option<f32>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub enum PostRating {
     #[serde(rename = "s")]
     Safe,
@@ -136,6 +147,20 @@ struct PostsResponse {
     posts: Vec<Post>,
 }
 
+#[derive(Deserialize)]
+struct PostResponse {
+    post: Post,
+}
+
+/// A pool: an ordered sequence of posts, typically a comic or a set of
+/// pages meant to be read in a specific order.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Pool {
+    pub id: u32,
+    pub name: String,
+    pub post_ids: Vec<u32>,
+}
+
 #[derive(Debug)]
 struct ApiError {
     details: String
@@ -159,43 +184,184 @@ impl Error for ApiError {
     }
 }
 
-pub async fn download(post: &Post, to: &Path) -> Result<(), Box<dyn Error>> {
+/// Downloads `post`'s file to `to`, verifying it against the post's MD5
+/// once the stream completes and retrying with exponential backoff on
+/// mismatch. If `to` already exists and its MD5 matches, the network is
+/// skipped entirely, making re-runs idempotent. `max_retries` is floored at
+/// 1 so `0` can't silently turn this into a no-op.
+pub async fn download(post: &Post, to: &Path, limiter: &RateLimiter, credentials: Option<&Credentials>, max_retries: u32, progress_bar: Option<&ProgressBar>) -> Result<(), Box<dyn Error>> {
     let url = post.file.url.as_ref()
         .ok_or(ApiError::new("post has no downloadable file (a tag might be blacklisted)"))?;
 
-    let mut file = File::create(to)?;
+    if let Ok(existing) = fs::read(to) {
+        if format!("{:x}", md5::compute(&existing)) == post.file.md5 {
+            debug!("Post {} already downloaded and MD5 matches; skipping.", post.id);
+            if let Some(bar) = progress_bar {
+                bar.finish_and_clear();
+            }
+            return Ok(());
+        }
+    }
 
-    let res = reqwest::get(url).await?;
+    let max_retries = max_retries.max(1);
+    let mut delay = Duration::from_secs(1);
+    let mut last_err: Option<Box<dyn Error>> = None;
+
+    for attempt in 1..=max_retries {
+        if let Some(bar) = progress_bar {
+            bar.reset();
+        }
+
+        match download_attempt(url, to, &post.file.md5, limiter, credentials, progress_bar).await {
+            Ok(_) => {
+                if let Some(bar) = progress_bar {
+                    bar.finish_and_clear();
+                }
+                return Ok(());
+            },
+            Err(e) => {
+                warn!("Download attempt {}/{} for post {} failed: {}", attempt, max_retries, post.id, e);
+                let _ = fs::remove_file(to);
+                last_err = Some(e);
+
+                if attempt < max_retries {
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+        }
+    }
+
+    if let Some(bar) = progress_bar {
+        bar.finish_and_clear();
+    }
+
+    Err(last_err.unwrap_or_else(|| Box::new(ApiError::new("download failed for an unknown reason"))))
+}
+
+async fn download_attempt(url: &str, to: &Path, expected_md5: &str, limiter: &RateLimiter, credentials: Option<&Credentials>, progress_bar: Option<&ProgressBar>) -> Result<(), Box<dyn Error>> {
+    let client = Client::new();
+    let mut req = client.get(url)
+        .header(reqwest::header::USER_AGENT, user_agent(credentials));
+
+    if let Some(creds) = credentials {
+        req = req.basic_auth(&creds.login, Some(&creds.api_key));
+    }
+
+    limiter.acquire().await;
+    let res = req.send().await?;
+
+    if let Some(bar) = progress_bar {
+        // Leave the bar as an indeterminate spinner when the server doesn't
+        // report a length, rather than turning it into a "{bytes}/0 B" bar.
+        if let Some(len) = res.content_length() {
+            bar.set_length(len);
+        }
+    }
+
+    let mut file = File::create(to)?;
+    let mut context = md5::Context::new();
 
     let mut download_stream = res.bytes_stream();
     while let Some(bytes) = download_stream.next().await {
-        if let Err(e) = file.write_all(&bytes?) {
-            return Err(Box::new(e));
+        let bytes = bytes?;
+        context.consume(&bytes);
+        file.write_all(&bytes)?;
+
+        if let Some(bar) = progress_bar {
+            bar.inc(bytes.len() as u64);
         }
     }
 
+    let digest = format!("{:x}", context.compute());
+    if digest != expected_md5 {
+        return Err(Box::new(ApiError::new(&format!("MD5 mismatch (expected {}, got {})", expected_md5, digest))));
+    }
+
     Ok(())
 }
 
-pub async fn search(tags: &str, limit: u32, page: &str, sfw: bool) -> Result<Vec<Post>, Box<dyn std::error::Error>> {
+pub async fn search(tags: &str, limit: u32, page: &str, sfw: bool, limiter: &RateLimiter, credentials: Option<&Credentials>) -> Result<Vec<Post>, Box<dyn std::error::Error>> {
     let client = Client::new();
 
     debug!("Sending search request (tags = {}, limit = {}, page = {}, sfw = {})", tags, limit, page, sfw);
 
-    let domain = if sfw { "e926.net" } else { "e621.net" };
+    let domain = domain(sfw);
 
     debug!("Using domain {}", domain);
 
-    let res = client.get(&format!("https://{}/posts.json", domain))
-        .header(reqwest::header::USER_AGENT, "e6dl: rust edition (@tjhorner on Telegram)")
+    let mut req = client.get(&format!("https://{}/posts.json", domain))
+        .header(reqwest::header::USER_AGENT, user_agent(credentials))
         .query(&[
             ("tags", tags),
             ("page", page),
             ("limit", &limit.to_string()),
-        ])
-        .send()
-        .await?;
+        ]);
+
+    if let Some(creds) = credentials {
+        req = req.basic_auth(&creds.login, Some(&creds.api_key));
+    }
+
+    limiter.acquire().await;
+    let res = req.send().await?;
 
     let pr = res.json::<PostsResponse>().await?;
     Ok(pr.posts)
+}
+
+/// Fetches a single pool by id, which gives the ordered `post_ids` that
+/// make up its pages.
+pub async fn fetch_pool(pool_id: u32, sfw: bool, limiter: &RateLimiter, credentials: Option<&Credentials>) -> Result<Pool, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let domain = domain(sfw);
+
+    debug!("Fetching pool {} from {}", pool_id, domain);
+
+    let mut req = client.get(&format!("https://{}/pools.json", domain))
+        .header(reqwest::header::USER_AGENT, user_agent(credentials))
+        .query(&[("search[id]", pool_id.to_string())]);
+
+    if let Some(creds) = credentials {
+        req = req.basic_auth(&creds.login, Some(&creds.api_key));
+    }
+
+    limiter.acquire().await;
+    let res = req.send().await?;
+
+    let mut pools = res.json::<Vec<Pool>>().await?;
+    pools.pop().ok_or_else(|| Box::new(ApiError::new(&format!("no pool found with id {}", pool_id))) as Box<dyn std::error::Error>)
+}
+
+/// Fetches a single post by id.
+pub async fn fetch_post(post_id: u32, sfw: bool, limiter: &RateLimiter, credentials: Option<&Credentials>) -> Result<Post, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let domain = domain(sfw);
+
+    debug!("Fetching post {} from {}", post_id, domain);
+
+    let mut req = client.get(&format!("https://{}/posts/{}.json", domain, post_id))
+        .header(reqwest::header::USER_AGENT, user_agent(credentials));
+
+    if let Some(creds) = credentials {
+        req = req.basic_auth(&creds.login, Some(&creds.api_key));
+    }
+
+    limiter.acquire().await;
+    let res = req.send().await?;
+
+    let pr = res.json::<PostResponse>().await?;
+    Ok(pr.post)
+}
+
+fn domain(sfw: bool) -> &'static str {
+    if sfw { "e926.net" } else { "e621.net" }
+}
+
+/// Builds the `User-Agent` header, appending the authenticated login (if
+/// any) so requests identify the account as e621's API terms require.
+fn user_agent(credentials: Option<&Credentials>) -> String {
+    match credentials {
+        Some(creds) => format!("e6dl: rust edition (@tjhorner on Telegram) - {}", creds.login),
+        None => "e6dl: rust edition (@tjhorner on Telegram)".to_string()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,51 @@
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::errors::ParseError;
+
+/// Mirrors the subset of `Cli`'s fields that are worth saving as reusable
+/// defaults, loaded from `~/.config/e6dl/config.toml`. Explicit CLI
+/// arguments always take precedence over values loaded here.
+#[derive(Deserialize, Default, Debug)]
+pub struct FileConfig {
+    pub out: Option<PathBuf>,
+    pub sfw: Option<bool>,
+    pub concurrency: Option<usize>,
+    pub limit: Option<u32>,
+    pub group: Option<Vec<String>>,
+    pub rate_limit: Option<f64>,
+    pub login: Option<String>,
+    pub api_key: Option<String>,
+    pub dedupe: Option<bool>,
+    pub dedupe_threshold: Option<u32>,
+    pub max_retries: Option<u32>,
+    pub no_progress: Option<bool>,
+    pub metadata: Option<bool>,
+}
+
+/// Loads the config file at `~/.config/e6dl/config.toml`, if one exists.
+/// Returns `Ok(None)` when there is no config directory or no file there,
+/// so callers fall back to built-in defaults.
+pub fn load() -> Result<Option<FileConfig>, ParseError> {
+    let path = match config_path() {
+        Some(path) => path,
+        None => return Ok(None)
+    };
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| ParseError::new(&format!("couldn't read config file \"{}\": {}", path.to_string_lossy(), e)))?;
+
+    let config = toml::from_str(&contents)
+        .map_err(|e| ParseError::new(&format!("couldn't parse config file \"{}\": {}", path.to_string_lossy(), e)))?;
+
+    Ok(Some(config))
+}
+
+fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("e6dl").join("config.toml"))
+}
@@ -0,0 +1,121 @@
+use image::GenericImageView;
+use log::warn;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const INDEX_FILE: &str = ".e6dl-dedupe-index";
+
+/// Tracks perceptual hashes of already-downloaded images so visual
+/// duplicates can be skipped when re-running overlapping tag searches.
+pub struct DedupeIndex {
+    threshold: u32,
+    hashes: Vec<(PathBuf, u64)>,
+}
+
+impl DedupeIndex {
+    /// Loads the sidecar index from `out` (if present) and seeds it with
+    /// hashes of any files already in the directory that aren't yet recorded.
+    pub fn load(out: &Path, threshold: u32) -> DedupeIndex {
+        let mut hashes = Vec::new();
+
+        if let Ok(contents) = fs::read_to_string(out.join(INDEX_FILE)) {
+            for line in contents.lines() {
+                if let Some((hash_str, path_str)) = line.split_once(' ') {
+                    if let Ok(hash) = u64::from_str_radix(hash_str, 16) {
+                        hashes.push((PathBuf::from(path_str), hash));
+                    }
+                }
+            }
+        }
+
+        let mut index = DedupeIndex { threshold, hashes };
+        index.seed_existing_files(out);
+        index
+    }
+
+    /// Recurses into subdirectories so files written under `--group` (e.g.
+    /// `pool_<id>/`, `safe/`, ...) are seeded too.
+    fn seed_existing_files(&mut self, out: &Path) {
+        let entries = match fs::read_dir(out) {
+            Ok(entries) => entries,
+            Err(_) => return
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            if path.is_dir() {
+                self.seed_existing_files(&path);
+                continue;
+            }
+
+            if !path.is_file() || self.hashes.iter().any(|(p, _)| p == &path) {
+                continue;
+            }
+
+            if let Ok(bytes) = fs::read(&path) {
+                if let Some(hash) = dhash(&bytes) {
+                    self.hashes.push((path, hash));
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if `hash` is a Hamming distance of less than the
+    /// configured threshold from any hash already in the index, other than `exclude`
+    /// itself (so a freshly (re-)downloaded file already seeded in the
+    /// index isn't compared against, and deleted as a "duplicate" of itself).
+    pub fn is_duplicate(&self, hash: u64, exclude: &Path) -> bool {
+        self.hashes.iter()
+            .any(|(path, existing)| path != exclude && (existing ^ hash).count_ones() < self.threshold)
+    }
+
+    /// Inserts `path`/`hash`, replacing any existing entry for the same
+    /// path (e.g. one seeded at startup) instead of duplicating it.
+    pub fn insert(&mut self, path: PathBuf, hash: u64) {
+        self.hashes.retain(|(p, _)| p != &path);
+        self.hashes.push((path, hash));
+    }
+
+    /// Removes `path`'s entry, if any. Callers must do this when deleting a
+    /// file as a duplicate so its stale hash can't go on to match (and
+    /// delete) other entries that were only ever duplicates of it.
+    pub fn remove(&mut self, path: &Path) {
+        self.hashes.retain(|(p, _)| p != path);
+    }
+
+    /// Persists the index to a sidecar file in `out` so repeated invocations
+    /// don't need to rehash every existing file.
+    pub fn save(&self, out: &Path) {
+        let contents = self.hashes.iter()
+            .map(|(path, hash)| format!("{:016x} {}", hash, path.to_string_lossy()))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if let Err(e) = fs::write(out.join(INDEX_FILE), contents) {
+            warn!("Could not persist dedupe index: {}", e);
+        }
+    }
+}
+
+/// Computes a 64-bit difference hash (dHash) of the image bytes: resize to
+/// 9x8 grayscale, then for each row emit a bit per pixel indicating whether
+/// it's brighter than its right neighbor, yielding 8x8 = 64 bits.
+pub fn dhash(bytes: &[u8]) -> Option<u64> {
+    let img = image::load_from_memory(bytes).ok()?;
+    let small = img.resize_exact(9, 8, image::imageops::FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            let left = small.get_pixel(x, y)[0];
+            let right = small.get_pixel(x + 1, y)[0];
+            if left > right {
+                hash |= 1;
+            }
+        }
+    }
+
+    Some(hash)
+}
@@ -10,7 +10,39 @@ use std::str::FromStr;
 extern crate pretty_env_logger;
 
 mod api;
+mod config;
+mod dedupe;
 mod errors;
+mod metadata;
+mod progress;
+mod ratelimit;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Maps post ids to their 1-based position in a pool, so files can be named
+/// by reading order (`001.png`, `002.png`, ...) instead of by post id.
+struct PoolSequencing {
+    positions: HashMap<u32, usize>,
+    width: usize,
+}
+
+impl PoolSequencing {
+    fn new(post_ids: &[u32]) -> PoolSequencing {
+        let width = std::cmp::max(3, post_ids.len().to_string().len());
+        let positions = post_ids.iter()
+            .enumerate()
+            .map(|(i, id)| (*id, i + 1))
+            .collect();
+
+        PoolSequencing { positions, width }
+    }
+
+    fn file_stem(&self, post_id: u32) -> Option<String> {
+        self.positions.get(&post_id).map(|pos| format!("{:0width$}", pos, width = self.width))
+    }
+}
 
 #[derive(Debug)]
 enum PostGrouping {
@@ -64,12 +96,14 @@ impl FromStr for PostGrouping {
 #[derive(StructOpt, Debug)]
 struct Cli {
     /// The tags to search for, space-separated. See: https://e621.net/help/cheatsheet
-    tags: String,
+    ///
+    /// Not required when `pool` is used.
+    tags: Option<String>,
 
     /// The maximum number of posts that should be retrieved per page.
-    /// There is a hard limit of 320.
-    #[structopt(short, long, default_value = "10")]
-    limit: u32,
+    /// There is a hard limit of 320. Falls back to the config file, then 10.
+    #[structopt(short, long)]
+    limit: Option<u32>,
 
     /// The page that will be retrieved. Can also be used with "a" or "b" + `post_id`
     /// to get the posts after or before the specified post ID. For example, "a13"
@@ -87,22 +121,171 @@ struct Cli {
     #[structopt(short, long, default_value = "1")]
     pages: u32,
 
-    /// The directory to write the downloaded posts to.
-    #[structopt(short, long, default_value = "./out", parse(from_os_str))]
-    out: PathBuf,
+    /// The directory to write the downloaded posts to. Falls back to the
+    /// config file, then `./out`.
+    #[structopt(short, long, parse(from_os_str))]
+    out: Option<PathBuf>,
 
-    /// Download posts from e926 instead of e621.
+    /// Download posts from e926 instead of e621. Falls back to the config
+    /// file, then `false`.
     #[structopt(short, long)]
     sfw: bool,
 
-    /// Maximum number of concurrent downloads.
-    #[structopt(short, long, default_value = "5")]
-    concurrency: usize,
+    /// Force `sfw` off, overriding a config file that sets it to `true`.
+    #[structopt(long)]
+    no_sfw: bool,
+
+    /// Maximum number of concurrent downloads. Falls back to the config
+    /// file, then 5.
+    #[structopt(short, long)]
+    concurrency: Option<usize>,
 
     /// Save downloaded posts grouped by the specified groupings. You can specify
     /// multiple groupings. See: https://github.com/tjhorner/e6dl-rs/wiki/Post-Grouping
+    ///
+    /// Falls back to the config file's `group` list when none are given here.
     #[structopt(short, long)]
     group: Vec<PostGrouping>,
+
+    /// Maximum number of requests per second to send to the API. This is shared
+    /// across all concurrent searches and downloads to avoid exceeding e621's
+    /// documented rate limit. Non-positive values are floored to a tiny
+    /// positive rate rather than rejected. Falls back to the config file, then 2.
+    #[structopt(long)]
+    rate_limit: Option<f64>,
+
+    /// e621/e926 username to authenticate with. Must be used together with
+    /// `api_key`. Authenticating unlocks posts hidden behind the default
+    /// blacklist for anonymous users. Falls back to the config file, which
+    /// keeps this out of shell history.
+    #[structopt(long)]
+    login: Option<String>,
+
+    /// API key for the account specified by `login`. You can generate one
+    /// from your account settings page. Falls back to the config file.
+    #[structopt(long)]
+    api_key: Option<String>,
+
+    /// Skip downloading images that are visual duplicates of files already
+    /// present in `out`, useful when re-running overlapping tag searches.
+    #[structopt(long)]
+    dedupe: bool,
+
+    /// Force `dedupe` off, overriding a config file that sets it to `true`.
+    #[structopt(long)]
+    no_dedupe: bool,
+
+    /// Hamming distance between perceptual hashes below which two images are
+    /// considered duplicates (a distance equal to it is not). Only used with
+    /// `--dedupe`. Falls back to the config file, then 10.
+    #[structopt(long)]
+    dedupe_threshold: Option<u32>,
+
+    /// Maximum number of attempts to download a post's file before giving
+    /// up, with exponential backoff between attempts. Floored at 1, so `0`
+    /// still makes one attempt rather than skipping the download entirely.
+    /// Falls back to the config file, then 3.
+    #[structopt(long)]
+    max_retries: Option<u32>,
+
+    /// Disable the live progress bars and fall back to plain log lines,
+    /// even when connected to a terminal.
+    #[structopt(long)]
+    no_progress: bool,
+
+    /// Force progress bars back on, overriding a config file that sets
+    /// `no_progress` to `true`.
+    #[structopt(long)]
+    progress: bool,
+
+    /// Write a JSON metadata sidecar next to each downloaded post, named like
+    /// the post's file with a `.json` extension (`<id>.json`, or `001.json`
+    /// etc. with `pool`),
+    /// plus a `manifest.json` at the root of `out` summarizing every post
+    /// downloaded this run, so provenance can be archived or re-derived.
+    #[structopt(long)]
+    metadata: bool,
+
+    /// Force `metadata` off, overriding a config file that sets it to `true`.
+    #[structopt(long)]
+    no_metadata: bool,
+
+    /// Download every post in the given pool, in the pool's own page order,
+    /// naming files by zero-padded sequence index (`001.png`, `002.png`, ...)
+    /// instead of post id. Overrides `tags`/`page`/`pages`.
+    #[structopt(long)]
+    pool: Option<u32>,
+}
+
+/// The fully-resolved set of options the rest of the program runs with,
+/// merged from explicit CLI arguments (highest precedence), the config
+/// file, and finally built-in defaults.
+struct Settings {
+    tags: Option<String>,
+    page: String,
+    pages: u32,
+    pool: Option<u32>,
+    limit: u32,
+    out: PathBuf,
+    sfw: bool,
+    concurrency: usize,
+    group: Vec<PostGrouping>,
+    rate_limit: f64,
+    login: Option<String>,
+    api_key: Option<String>,
+    dedupe: bool,
+    dedupe_threshold: u32,
+    max_retries: u32,
+    no_progress: bool,
+    metadata: bool,
+}
+
+/// Resolves a CLI flag pair (`on`/`off`) against the config file, letting an
+/// explicit CLI flag win either way instead of only ever being able to turn
+/// a config-file `true` on further.
+fn resolve_flag(on: bool, off: bool, file_value: Option<bool>, default: bool) -> bool {
+    if on {
+        true
+    } else if off {
+        false
+    } else {
+        file_value.unwrap_or(default)
+    }
+}
+
+impl Settings {
+    fn resolve(args: Cli, file_config: Option<config::FileConfig>) -> Result<Settings, errors::ParseError> {
+        let file_config = file_config.unwrap_or_default();
+
+        let mut group = args.group;
+        if group.is_empty() {
+            if let Some(names) = file_config.group {
+                for name in names {
+                    group.push(name.parse()?);
+                }
+            }
+        }
+
+        Ok(Settings {
+            tags: args.tags,
+            page: args.page,
+            pages: args.pages,
+            pool: args.pool,
+            limit: args.limit.or(file_config.limit).unwrap_or(10),
+            out: args.out.or(file_config.out).unwrap_or_else(|| PathBuf::from("./out")),
+            sfw: resolve_flag(args.sfw, args.no_sfw, file_config.sfw, false),
+            concurrency: args.concurrency.or(file_config.concurrency).unwrap_or(5),
+            group,
+            rate_limit: args.rate_limit.or(file_config.rate_limit).unwrap_or(2.0),
+            login: args.login.or(file_config.login),
+            api_key: args.api_key.or(file_config.api_key),
+            dedupe: resolve_flag(args.dedupe, args.no_dedupe, file_config.dedupe, false),
+            dedupe_threshold: args.dedupe_threshold.or(file_config.dedupe_threshold).unwrap_or(10),
+            max_retries: args.max_retries.or(file_config.max_retries).unwrap_or(3),
+            no_progress: resolve_flag(args.no_progress, args.progress, file_config.no_progress, false),
+            metadata: resolve_flag(args.metadata, args.no_metadata, file_config.metadata, false),
+        })
+    }
 }
 
 #[tokio::main]
@@ -121,20 +304,70 @@ async fn main() -> Result<(), Box<dyn Error>> {
         process::exit(1);
     }
 
-    info!("Searching for \"{}\"...", args.tags);
+    if args.pool.is_none() && args.tags.is_none() {
+        error!("The `tags` argument is required unless `pool` is specified.");
+        process::exit(1);
+    }
 
-    let results = collect_posts(&args).await;
+    let file_config = config::load()?;
+    let settings = Settings::resolve(args, file_config)?;
+
+    match &settings.pool {
+        Some(pool_id) => info!("Collecting posts from pool {}...", pool_id),
+        None => info!("Searching for \"{}\"...", settings.tags.as_ref().unwrap())
+    }
+
+    let limiter = ratelimit::RateLimiter::new(settings.rate_limit);
+
+    let credentials = match (&settings.login, &settings.api_key) {
+        (Some(login), Some(api_key)) => Some(api::Credentials { login: login.clone(), api_key: api_key.clone() }),
+        (None, None) => None,
+        _ => {
+            error!("`login` and `api-key` must be specified together.");
+            process::exit(1);
+        }
+    };
+
+    let results = collect_posts(&settings, &limiter, credentials.as_ref()).await;
 
     match results {
-        Ok(posts) => {
+        Ok((posts, pool_sequencing)) => {
             if posts.is_empty() {
                 warn!("No posts to download!");
                 return Ok(());
             }
 
-            let out_dir = args.out.as_path();
+            let out_dir = settings.out.as_path();
             info!("Found {} posts matching criteria, downloading to \"{}\"...", posts.len(), out_dir.to_str().unwrap());
-            download_all(&posts, out_dir, &args.group, args.concurrency).await?;
+
+            let dedupe_index = if settings.dedupe {
+                fs::create_dir_all(out_dir)?;
+                info!("Indexing existing files in \"{}\" for deduplication...", out_dir.to_str().unwrap());
+                Some(Arc::new(Mutex::new(dedupe::DedupeIndex::load(out_dir, settings.dedupe_threshold))))
+            } else {
+                None
+            };
+
+            let show_progress = !settings.no_progress
+                && atty::is(atty::Stream::Stdout)
+                && std::env::var("E6DL_LOG").map_or(true, |v| v != "debug");
+
+            let manifest = if settings.metadata {
+                fs::create_dir_all(out_dir)?;
+                Some(Arc::new(Mutex::new(metadata::Manifest::new())))
+            } else {
+                None
+            };
+
+            download_all(&posts, out_dir, &settings.group, settings.concurrency, &limiter, credentials.as_ref(), dedupe_index.as_ref(), settings.max_retries, show_progress, manifest.as_ref(), pool_sequencing.as_ref()).await?;
+
+            if let Some(index) = &dedupe_index {
+                index.lock().await.save(out_dir);
+            }
+
+            if let Some(manifest) = &manifest {
+                manifest.lock().await.save(out_dir);
+            }
         },
         Err(e) => error!("Could not search for posts: {}", e)
     }
@@ -142,10 +375,20 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-async fn collect_posts(args: &Cli) -> Result<Vec<api::Post>, Box<dyn std::error::Error>> {
+async fn collect_posts(args: &Settings, limiter: &ratelimit::RateLimiter, credentials: Option<&api::Credentials>) -> Result<(Vec<api::Post>, Option<PoolSequencing>), Box<dyn std::error::Error>> {
+    if let Some(pool_id) = args.pool {
+        let posts = collect_pool_posts(pool_id, args.sfw, limiter, credentials).await?;
+        let sequencing = PoolSequencing::new(&posts.iter().map(|p| p.id).collect::<Vec<_>>());
+        return Ok((posts, Some(sequencing)));
+    }
+
+    // Tags is guaranteed to be present when `pool` isn't (checked in main).
+    let tags = args.tags.as_ref().unwrap();
+
     if args.pages == 1 {
         info!("Collecting posts from page {}...", args.page);
-        return api::search(&args.tags, args.limit, &args.page, args.sfw).await;
+        let posts = api::search(tags, args.limit, &args.page, args.sfw, limiter, credentials).await?;
+        return Ok((posts, None));
     }
 
     let mut all_posts = Vec::new();
@@ -158,7 +401,7 @@ async fn collect_posts(args: &Cli) -> Result<Vec<api::Post>, Box<dyn std::error:
 
     for page_num in starting_page..ending_page {
         debug!("Collecting posts from page {}...", page_num);
-        let results = api::search(&args.tags, args.limit, &page_num.to_string(), args.sfw).await;
+        let results = api::search(tags, args.limit, &page_num.to_string(), args.sfw, limiter, credentials).await;
 
         match results {
             Ok(mut posts) => {
@@ -173,10 +416,27 @@ async fn collect_posts(args: &Cli) -> Result<Vec<api::Post>, Box<dyn std::error:
         }
     }
 
-    Ok(all_posts)
+    Ok((all_posts, None))
+}
+
+async fn collect_pool_posts(pool_id: u32, sfw: bool, limiter: &ratelimit::RateLimiter, credentials: Option<&api::Credentials>) -> Result<Vec<api::Post>, Box<dyn std::error::Error>> {
+    let pool = api::fetch_pool(pool_id, sfw, limiter, credentials).await?;
+
+    info!("Fetching {} posts from pool \"{}\"...", pool.post_ids.len(), pool.name);
+
+    let mut posts = Vec::with_capacity(pool.post_ids.len());
+
+    for post_id in pool.post_ids {
+        match api::fetch_post(post_id, sfw, limiter, credentials).await {
+            Ok(post) => posts.push(post),
+            Err(e) => error!("Could not fetch post {} from pool: {}", post_id, e)
+        }
+    }
+
+    Ok(posts)
 }
 
-async fn download(post: &api::Post, to: &Path, grouping: &Vec<PostGrouping>) {
+async fn download(post: &api::Post, to: &Path, grouping: &Vec<PostGrouping>, limiter: &ratelimit::RateLimiter, credentials: Option<&api::Credentials>, dedupe_index: Option<&Arc<Mutex<dedupe::DedupeIndex>>>, max_retries: u32, reporter: &progress::Reporter, manifest: Option<&Arc<Mutex<metadata::Manifest>>>, pool_sequencing: Option<&PoolSequencing>) {
     let mut file_name = to.to_path_buf();
 
     if !grouping.is_empty() {
@@ -190,24 +450,87 @@ async fn download(post: &api::Post, to: &Path, grouping: &Vec<PostGrouping>) {
         }
     }
 
-    file_name.push(format!("{}.{}", post.id, post.file.ext));
+    let file_stem = pool_sequencing
+        .and_then(|seq| seq.file_stem(post.id))
+        .unwrap_or_else(|| post.id.to_string());
 
-    info!("Downloading post {} -> {}...", post.id, file_name.to_str().unwrap());
-    let result = api::download(post, &file_name).await;
+    file_name.push(format!("{}.{}", file_stem, post.file.ext));
+
+    let download_bar = reporter.add_download_bar();
+    if let Some(bar) = &download_bar {
+        bar.set_message(format!("post {}", post.id));
+    } else {
+        info!("Downloading post {} -> {}...", post.id, file_name.to_str().unwrap());
+    }
+
+    let result = api::download(post, &file_name, limiter, credentials, max_retries, download_bar.as_ref()).await;
+    reporter.inc_overall();
 
     match result {
-        Ok(_) => debug!("Done downloading post {}", post.id),
+        Ok(_) => {
+            debug!("Done downloading post {}", post.id);
+
+            if let Some(index) = dedupe_index {
+                // Animated/video posts have no single frame to hash; always keep them.
+                if post.duration.is_none() {
+                    check_duplicate(post, &file_name, index).await;
+                }
+            }
+
+            if let Some(manifest) = manifest {
+                if file_name.exists() {
+                    manifest.lock().await.record(post, &file_name);
+                }
+            }
+        },
         Err(e) => error!("Error downloading post {}: {}", post.id, e)
     }
 }
 
-async fn download_all(posts: &Vec<api::Post>, to: &Path, grouping: &Vec<PostGrouping>, concurrency: usize) -> Result<(), Box<dyn std::error::Error>> {
+async fn check_duplicate(post: &api::Post, file_name: &Path, dedupe_index: &Arc<Mutex<dedupe::DedupeIndex>>) {
+    let bytes = match fs::read(file_name) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("Couldn't read post {} back for dedupe hashing: {}", post.id, e);
+            return;
+        }
+    };
+
+    let hash = match dedupe::dhash(&bytes) {
+        Some(hash) => hash,
+        None => {
+            debug!("Couldn't compute perceptual hash for post {} (unsupported format?)", post.id);
+            return;
+        }
+    };
+
+    let mut index = dedupe_index.lock().await;
+
+    if index.is_duplicate(hash, file_name) {
+        info!("Post {} is a visual duplicate of an existing file; skipping.", post.id);
+        if let Err(e) = fs::remove_file(file_name) {
+            warn!("Couldn't remove duplicate file for post {}: {}", post.id, e);
+        }
+        // Drop its own (possibly stale, seeded-at-startup) entry so it can't
+        // go on to match, and wrongly delete, other files it was compared
+        // against -- otherwise a whole cluster of mutual near-duplicates
+        // can end up with none of them surviving.
+        index.remove(file_name);
+    } else {
+        index.insert(file_name.to_path_buf(), hash);
+    }
+}
+
+async fn download_all(posts: &Vec<api::Post>, to: &Path, grouping: &Vec<PostGrouping>, concurrency: usize, limiter: &ratelimit::RateLimiter, credentials: Option<&api::Credentials>, dedupe_index: Option<&Arc<Mutex<dedupe::DedupeIndex>>>, max_retries: u32, show_progress: bool, manifest: Option<&Arc<Mutex<metadata::Manifest>>>, pool_sequencing: Option<&PoolSequencing>) -> Result<(), Box<dyn std::error::Error>> {
     fs::create_dir_all(&to)?;
 
+    let reporter = progress::Reporter::new(show_progress, posts.len() as u64);
+
     futures::stream::iter(posts)
-        .for_each_concurrent(concurrency, |post| download(post, to, grouping))
+        .for_each_concurrent(concurrency, |post| download(post, to, grouping, limiter, credentials, dedupe_index, max_retries, &reporter, manifest, pool_sequencing))
         .await;
 
+    reporter.finish();
     info!("Done!");
 
     Ok(())
@@ -0,0 +1,65 @@
+use serde::Serialize;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use log::warn;
+
+use crate::api::{Post, PostTags};
+
+/// Summary of one downloaded post, as recorded in `manifest.json`.
+#[derive(Serialize)]
+struct ManifestEntry {
+    id: u32,
+    path: PathBuf,
+    md5: String,
+    tags: PostTags,
+    sources: Vec<String>,
+}
+
+/// Accumulates a manifest of every post downloaded this run and writes it,
+/// along with per-post JSON sidecars, when `--metadata` is enabled.
+#[derive(Default)]
+pub struct Manifest {
+    entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new() -> Manifest {
+        Manifest::default()
+    }
+
+    /// Writes the post as JSON to `file_name` with its extension replaced by
+    /// `.json` (so the sidecar shares the file's `<id>` or pool-sequence
+    /// name) and records the post in the
+    /// in-memory manifest for later saving via `save`.
+    pub fn record(&mut self, post: &Post, file_name: &Path) {
+        let sidecar_path = file_name.with_extension("json");
+
+        match File::create(&sidecar_path) {
+            Ok(file) => if let Err(e) = serde_json::to_writer_pretty(file, post) {
+                warn!("Couldn't write metadata sidecar for post {}: {}", post.id, e);
+            },
+            Err(e) => warn!("Couldn't create metadata sidecar for post {}: {}", post.id, e)
+        }
+
+        self.entries.push(ManifestEntry {
+            id: post.id,
+            path: file_name.to_path_buf(),
+            md5: post.file.md5.clone(),
+            tags: post.tags.clone(),
+            sources: post.sources.clone(),
+        });
+    }
+
+    /// Writes `manifest.json` summarizing every post recorded so far.
+    pub fn save(&self, out: &Path) {
+        let manifest_path = out.join("manifest.json");
+
+        let result = File::create(&manifest_path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| serde_json::to_writer_pretty(file, &self.entries).map_err(|e| e.to_string()));
+
+        if let Err(e) = result {
+            warn!("Couldn't write manifest to \"{}\": {}", manifest_path.to_string_lossy(), e);
+        }
+    }
+}
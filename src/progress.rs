@@ -0,0 +1,59 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+/// Renders a live multi-progress download UI, or falls back to nothing
+/// (letting the existing `log` lines speak for themselves) when output
+/// isn't a TTY or the caller has opted out.
+pub enum Reporter {
+    Bars {
+        multi: MultiProgress,
+        overall: ProgressBar,
+    },
+    Log,
+}
+
+impl Reporter {
+    pub fn new(show: bool, total_posts: u64) -> Reporter {
+        if !show {
+            return Reporter::Log;
+        }
+
+        let multi = MultiProgress::new();
+
+        let overall = multi.add(ProgressBar::new(total_posts));
+        overall.set_style(
+            ProgressStyle::with_template("{msg} [{bar:40.cyan/blue}] {pos}/{len}")
+                .unwrap()
+                .progress_chars("#>-")
+        );
+        overall.set_message("Downloading posts");
+
+        Reporter::Bars { multi, overall }
+    }
+
+    /// Adds a transient per-download bar for tracking bytes transferred.
+    /// Returns `None` when progress bars are disabled.
+    pub fn add_download_bar(&self) -> Option<ProgressBar> {
+        match self {
+            Reporter::Bars { multi, .. } => {
+                let bar = multi.add(ProgressBar::new_spinner());
+                bar.set_style(
+                    ProgressStyle::with_template("  {spinner} {bytes}/{total_bytes} {msg}").unwrap()
+                );
+                Some(bar)
+            },
+            Reporter::Log => None
+        }
+    }
+
+    pub fn inc_overall(&self) {
+        if let Reporter::Bars { overall, .. } = self {
+            overall.inc(1);
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Reporter::Bars { overall, .. } = self {
+            overall.finish_with_message("Done");
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+/// Smallest rate we'll actually use; keeps `(1.0 - tokens) / rate` in
+/// `acquire()` finite even if the caller passes a non-positive rate.
+const MIN_RATE: f64 = 0.001;
+
+/// A simple async token-bucket rate limiter shared across tasks.
+///
+/// Tokens are refilled continuously at `rate` tokens/sec up to `capacity`
+/// (the allowed burst), and `acquire()` blocks until a token is available.
+#[derive(Clone)]
+pub struct RateLimiter {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter that allows `rate` requests per second, with a
+    /// burst capacity equal to `rate` floored at 1 token so rates below
+    /// 1/sec can still ever accumulate enough to let a request through.
+    /// `rate` itself is floored at `MIN_RATE` so a zero or negative value
+    /// can't turn the wait calculation in `acquire` into a division by zero.
+    pub fn new(rate: f64) -> RateLimiter {
+        let rate = rate.max(MIN_RATE);
+        let capacity = rate.max(1.0);
+
+        RateLimiter {
+            inner: Arc::new(Mutex::new(Inner {
+                capacity,
+                rate,
+                tokens: capacity,
+                last_refill: Instant::now(),
+            }))
+        }
+    }
+
+    /// Waits until a token is available, then consumes it.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut inner = self.inner.lock().await;
+
+                let now = Instant::now();
+                let elapsed = now.duration_since(inner.last_refill).as_secs_f64();
+                inner.tokens = (inner.tokens + elapsed * inner.rate).min(inner.capacity);
+                inner.last_refill = now;
+
+                if inner.tokens >= 1.0 {
+                    inner.tokens -= 1.0;
+                    None
+                } else {
+                    Some(Duration::from_secs_f64((1.0 - inner.tokens) / inner.rate))
+                }
+            };
+
+            match wait {
+                Some(d) => tokio::time::sleep(d).await,
+                None => return
+            }
+        }
+    }
+}